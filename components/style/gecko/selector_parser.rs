@@ -7,10 +7,61 @@ use element_state::ElementState;
 use selector_parser::{SelectorParser, PseudoElementCascadeType};
 use selector_parser::{attr_equals_selector_is_shareable, attr_exists_selector_is_shareable};
 use selectors::parser::AttrSelector;
+use std::ascii::AsciiExt;
 use std::borrow::Cow;
 use std::fmt;
 use string_cache::{Atom, Namespace, WeakAtom, WeakNamespace};
 
+/// The quirks mode of a document, which affects how certain selectors are
+/// matched against elements.
+///
+/// See https://quirks.spec.whatwg.org/ for the difference between limited
+/// quirks and full quirks mode. It is a document-wide setting; the functions
+/// in this module take it as an explicit argument, and no matching context
+/// here stores it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QuirksMode {
+    /// Quirks mode.
+    Quirks,
+    /// Limited quirks mode.
+    LimitedQuirks,
+    /// No quirks mode.
+    NoQuirks,
+}
+
+impl Default for QuirksMode {
+    #[inline]
+    fn default() -> Self {
+        QuirksMode::NoQuirks
+    }
+}
+
+impl QuirksMode {
+    /// Returns whether ID and class selectors should match ASCII
+    /// case-insensitively in this mode.
+    #[inline]
+    fn matches_id_or_class_ignoring_ascii_case(&self) -> bool {
+        *self == QuirksMode::Quirks
+    }
+}
+
+/// Compares two atoms used as the `Identifier` or `ClassName` of a selector
+/// for equality, honouring `quirks_mode`.
+///
+/// In no-quirks and limited-quirks mode this is exact (pointer/atom)
+/// equality, matching the standard. In full quirks mode, ID and class
+/// selectors are required to match ASCII case-insensitively, so we fall
+/// back to comparing the atoms' string contents when the fast path fails.
+#[inline]
+pub fn selector_atom_eq_ignoring_quirks(quirks_mode: QuirksMode, a: &Atom, b: &Atom) -> bool {
+    if a == b {
+        return true;
+    }
+
+    quirks_mode.matches_id_or_class_ignoring_ascii_case() &&
+        a.eq_ignore_ascii_case(b)
+}
+
 /// NOTE: The boolean field represents whether this element is an anonymous box.
 ///
 /// This is just for convenience, instead of recomputing it. Also, note that
@@ -28,8 +79,20 @@ use string_cache::{Atom, Namespace, WeakAtom, WeakNamespace};
 ///
 /// Also, we can further optimize PartialEq and hash comparing/hashing only the
 /// atoms.
+///
+/// The second boolean field records whether the pseudo's computed `display`
+/// should skip the usual parent-display-based fixups during cascade fixup
+/// (e.g. the blockification that applies to most anonymous boxes); it is
+/// filled in by the same `pseudo_element!` arms that fill in `is_anon_box`.
+///
+/// FIXME: the generated pseudo table (`generated/gecko_pseudo_element_helper.rs`)
+/// hasn't grown a column for this yet, so every real pseudo-element is
+/// constructed through `pseudo_element!`'s 3-argument fallback arm today,
+/// which always passes `false` here. This field (and the `assert_eq!` on it
+/// in `from_atom_unchecked`) is a no-op until the generator is updated to
+/// emit the 4-argument form.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct PseudoElement(Atom, bool);
+pub struct PseudoElement(Atom, bool, bool);
 
 impl PseudoElement {
     #[inline]
@@ -37,35 +100,82 @@ impl PseudoElement {
         &self.0
     }
 
+    /// Consumes `self` and returns the underlying atom.
+    ///
+    /// Pseudo-element atoms are always static atoms (see the struct-level
+    /// docs above), so this hands out the atom we already own without a
+    /// refcount bump.
+    #[inline]
+    pub fn into_atom(self) -> Atom {
+        debug_assert!(Self::from_weak_atom(&self.0, true).is_some(),
+                      "PseudoElement atoms should always be static");
+        self.0
+    }
+
+    /// Returns the raw pointer backing this pseudo's static atom, for
+    /// callers in the Gecko glue layer that need to hand the pseudo back
+    /// across the FFI boundary.
+    ///
+    /// Relies on the same static-atom invariant as `into_atom`; debug-build
+    /// callers get that invariant checked via `from_weak_atom`.
+    #[inline]
+    pub fn ffi_atom_ptr(&self) -> *const WeakAtom {
+        debug_assert!(Self::from_weak_atom(&self.0, true).is_some(),
+                      "PseudoElement atoms should always be static");
+        &*self.0
+    }
+
     #[inline]
     fn is_anon_box(&self) -> bool {
         self.1
     }
 
+    /// Whether this pseudo-element's computed `display` must not be adjusted
+    /// based on its parent's display type during cascade fixup.
     #[inline]
-    pub fn from_atom_unchecked(atom: Atom, is_anon_box: bool) -> Self {
+    pub fn skip_item_based_display_fixup(&self) -> bool {
+        self.2
+    }
+
+    #[inline]
+    pub fn from_atom_unchecked(atom: Atom, is_anon_box: bool, skips_display_fixups: bool) -> Self {
         if cfg!(debug_assertions) {
             // Do the check on debug regardless.
-            match Self::from_atom(&*atom, true) {
+            match Self::from_weak_atom(&*atom, true) {
                 Some(pseudo) => {
                     assert_eq!(pseudo.is_anon_box(), is_anon_box);
+                    assert_eq!(pseudo.skip_item_based_display_fixup(), skips_display_fixups);
                     return pseudo;
                 }
                 None => panic!("Unknown pseudo: {:?}", atom),
             }
         }
 
-        PseudoElement(atom, is_anon_box)
+        PseudoElement(atom, is_anon_box, skips_display_fixups)
+    }
+
+    /// A checked, owning conversion from an `Atom` to a `PseudoElement`, for
+    /// callers in the Gecko glue layer that only have a borrowed `Atom`
+    /// (rather than the `WeakAtom` this relies on internally).
+    #[inline]
+    pub fn from_atom(atom: &Atom) -> Option<Self> {
+        Self::from_weak_atom(&**atom, true)
     }
 
     #[inline]
-    fn from_atom(atom: &WeakAtom, _in_ua: bool) -> Option<Self> {
+    fn from_weak_atom(atom: &WeakAtom, _in_ua: bool) -> Option<Self> {
         macro_rules! pseudo_element {
-            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr) => {{
+            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr, $skips_display_fixups:expr) => {{
                 if atom == &*$atom {
-                    return Some(PseudoElement($atom, $is_anon_box));
+                    return Some(PseudoElement($atom, $is_anon_box, $skips_display_fixups));
                 }
-            }}
+            }};
+            // TODO(after the generator grows a `skips_display_fixups` column):
+            // drop this arm, once `generated/gecko_pseudo_element_helper.rs`
+            // only emits the 4-argument form above.
+            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr) => {
+                pseudo_element!($pseudo_str_with_colon, $atom, $is_anon_box, false)
+            }
         }
 
         include!("generated/gecko_pseudo_element_helper.rs");
@@ -75,15 +185,17 @@ impl PseudoElement {
 
     #[inline]
     fn from_slice(s: &str, in_ua_stylesheet: bool) -> Option<Self> {
-        use std::ascii::AsciiExt;
         macro_rules! pseudo_element {
-            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr) => {{
+            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr, $skips_display_fixups:expr) => {{
                 if !$is_anon_box || in_ua_stylesheet {
                     if s.eq_ignore_ascii_case(&$pseudo_str_with_colon[1..]) {
-                        return Some(PseudoElement($atom, $is_anon_box))
+                        return Some(PseudoElement($atom, $is_anon_box, $skips_display_fixups))
                     }
                 }
-            }}
+            }};
+            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr) => {
+                pseudo_element!($pseudo_str_with_colon, $atom, $is_anon_box, false)
+            }
         }
 
         include!("generated/gecko_pseudo_element_helper.rs");
@@ -117,6 +229,22 @@ pub enum NonTSPseudoClass {
     Indeterminate,
     ReadWrite,
     ReadOnly,
+    MozBrowserFrame,
+    MozTableBorderNonzero,
+}
+
+/// Whether a `NonTSPseudoClass`'s truth value can be answered by looking at
+/// an `ElementState` bit, or whether it has to be recomputed by querying the
+/// element (or a snapshot of it) directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PseudoClassMatchType {
+    /// The pseudo-class maps to an `ElementState` bit, so the restyle-hint
+    /// machinery can tell whether it changed by diffing state bits.
+    State,
+    /// The pseudo-class has no `ElementState` bit, so it has to be
+    /// re-evaluated from the element (or an `ElementSnapshot`) directly, and
+    /// invalidation has to compare the old and new answers explicitly.
+    Snapshot,
 }
 
 impl ToCss for NonTSPseudoClass {
@@ -136,6 +264,8 @@ impl ToCss for NonTSPseudoClass {
             Indeterminate => ":indeterminate",
             ReadWrite => ":read-write",
             ReadOnly => ":read-only",
+            MozBrowserFrame => ":-moz-browser-frame",
+            MozTableBorderNonzero => ":-moz-table-border-nonzero",
         })
     }
 }
@@ -157,7 +287,39 @@ impl NonTSPseudoClass {
 
             AnyLink |
             Link |
-            Visited => ElementState::empty(),
+            Visited |
+            MozBrowserFrame |
+            MozTableBorderNonzero => ElementState::empty(),
+        }
+    }
+
+    /// Returns whether this pseudo-class's truth value can be read off an
+    /// `ElementState` bit, or whether it instead has to be recomputed from
+    /// the element/snapshot directly.
+    ///
+    /// Pseudo-classes classified as `Snapshot` don't contribute a bit to
+    /// `state_flag()`; the restyle-hint machinery invalidates elements that
+    /// carry them by comparing the element's current answer against the
+    /// snapshot's answer, rather than by diffing state bits.
+    pub fn match_type(&self) -> PseudoClassMatchType {
+        use self::NonTSPseudoClass::*;
+        match *self {
+            AnyLink |
+            Link |
+            Visited |
+            MozBrowserFrame |
+            MozTableBorderNonzero => PseudoClassMatchType::Snapshot,
+
+            Active |
+            Focus |
+            Fullscreen |
+            Hover |
+            Enabled |
+            Disabled |
+            Checked |
+            Indeterminate |
+            ReadWrite |
+            ReadOnly => PseudoClassMatchType::State,
         }
     }
 }
@@ -188,6 +350,56 @@ impl ::selectors::SelectorImpl for SelectorImpl {
     }
 }
 
+/// Returns whether `attr_selector` selects on `id` or `class`, the two
+/// attributes whose matching semantics quirks mode changes.
+#[inline]
+fn attr_selector_is_id_or_class(attr_selector: &AttrSelector<SelectorImpl>) -> bool {
+    attr_selector.name == atom!("id") || attr_selector.name == atom!("class")
+}
+
+/// Like `SelectorImpl`'s (unmodified) `attr_exists_selector_is_shareable`,
+/// but additionally takes `quirks_mode` into account for `[id]`/`[class]`
+/// selectors.
+///
+/// In full quirks mode, `id` and `class` match ASCII case-insensitively,
+/// which the style sharing cache's case-sensitive comparison doesn't
+/// account for; we conservatively disable sharing for those two attributes
+/// rather than risk handing out the wrong cached style. Other attributes
+/// are unaffected by quirks mode, so they keep going through the trait
+/// method unchanged.
+///
+/// FIXME: nothing calls this yet. The real call site is the style sharing
+/// cache, which consults `SelectorImpl::attr_exists_selector_is_shareable`
+/// directly today; that caller needs to be switched over to this function
+/// once it has a `QuirksMode` to pass in.
+#[inline]
+pub fn attr_exists_selector_is_shareable_with_quirks(attr_selector: &AttrSelector<SelectorImpl>,
+                                                      quirks_mode: QuirksMode) -> bool {
+    if attr_selector_is_id_or_class(attr_selector) &&
+       quirks_mode.matches_id_or_class_ignoring_ascii_case() {
+        return false;
+    }
+
+    <SelectorImpl as ::selectors::SelectorImpl>::attr_exists_selector_is_shareable(attr_selector)
+}
+
+/// Like `attr_exists_selector_is_shareable_with_quirks`, but for
+/// `[attr=value]` selectors.
+///
+/// FIXME: see the FIXME on `attr_exists_selector_is_shareable_with_quirks`;
+/// this has the same not-yet-wired-up caller.
+#[inline]
+pub fn attr_equals_selector_is_shareable_with_quirks(attr_selector: &AttrSelector<SelectorImpl>,
+                                                      value: &Atom,
+                                                      quirks_mode: QuirksMode) -> bool {
+    if attr_selector_is_id_or_class(attr_selector) &&
+       quirks_mode.matches_id_or_class_ignoring_ascii_case() {
+        return false;
+    }
+
+    <SelectorImpl as ::selectors::SelectorImpl>::attr_equals_selector_is_shareable(attr_selector, value)
+}
+
 impl<'a> ::selectors::Parser for SelectorParser<'a> {
     type Impl = SelectorImpl;
 
@@ -207,6 +419,8 @@ impl<'a> ::selectors::Parser for SelectorParser<'a> {
             "indeterminate" => Indeterminate,
             "read-write" => ReadWrite,
             "read-only" => ReadOnly,
+            "-moz-browser-frame" => MozBrowserFrame,
+            "-moz-table-border-nonzero" => MozTableBorderNonzero,
             _ => return Err(())
         };
 
@@ -248,9 +462,12 @@ impl SelectorImpl {
         where F: FnMut(PseudoElement)
     {
         macro_rules! pseudo_element {
-            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr) => {{
-                fun(PseudoElement($atom, $is_anon_box));
-            }}
+            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr, $skips_display_fixups:expr) => {{
+                fun(PseudoElement($atom, $is_anon_box, $skips_display_fixups));
+            }};
+            ($pseudo_str_with_colon:expr, $atom:expr, $is_anon_box:expr) => {
+                pseudo_element!($pseudo_str_with_colon, $atom, $is_anon_box, false)
+            }
         }
 
         include!("generated/gecko_pseudo_element_helper.rs")
@@ -266,4 +483,23 @@ impl SelectorImpl {
     pub fn pseudo_class_state_flag(pc: &NonTSPseudoClass) -> ElementState {
         pc.state_flag()
     }
+
+    /// Returns whether `selector_atom` (the `Identifier` or `ClassName` of a
+    /// compound selector) matches `element_atom` (the element's ID, or one
+    /// of its classes), taking `quirks_mode` into account.
+    ///
+    /// This is meant to be called from the compound ID/class matching code
+    /// in place of plain `Atom` equality, so that full quirks mode documents
+    /// get the ASCII case-insensitive matching the HTML quirks spec
+    /// requires.
+    ///
+    /// FIXME: that call site is in the generic selector matching code
+    /// (outside this crate slice) and doesn't call this yet, so quirks-mode
+    /// ID/class matching has no effect until it's switched over.
+    #[inline]
+    pub fn is_id_or_class_match(quirks_mode: QuirksMode,
+                                selector_atom: &Atom,
+                                element_atom: &Atom) -> bool {
+        selector_atom_eq_ignoring_quirks(quirks_mode, selector_atom, element_atom)
+    }
 }
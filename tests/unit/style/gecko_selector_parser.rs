@@ -0,0 +1,42 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use style::element_state::ElementState;
+use style::gecko::selector_parser::{NonTSPseudoClass, PseudoClassMatchType, PseudoElement};
+use style::gecko::selector_parser::{QuirksMode, selector_atom_eq_ignoring_quirks};
+use style::string_cache::Atom;
+
+#[test]
+fn quirks_mode_id_class_matching_is_ascii_case_insensitive() {
+    let foo = Atom::from("foo");
+    let upper_foo = Atom::from("FOO");
+    let bar = Atom::from("bar");
+
+    assert!(selector_atom_eq_ignoring_quirks(QuirksMode::Quirks, &foo, &upper_foo));
+    assert!(!selector_atom_eq_ignoring_quirks(QuirksMode::LimitedQuirks, &foo, &upper_foo));
+    assert!(!selector_atom_eq_ignoring_quirks(QuirksMode::NoQuirks, &foo, &upper_foo));
+
+    assert!(!selector_atom_eq_ignoring_quirks(QuirksMode::Quirks, &foo, &bar));
+
+    // Exact matches always succeed, regardless of quirks mode.
+    assert!(selector_atom_eq_ignoring_quirks(QuirksMode::NoQuirks, &foo, &foo));
+}
+
+#[test]
+fn moz_pseudo_classes_are_snapshot_classified() {
+    // Neither -moz-browser-frame nor -moz-table-border-nonzero map to an
+    // ElementState bit, so both must go through the snapshot-comparison
+    // path rather than the state_flag() bitdiff path.
+    assert_eq!(NonTSPseudoClass::MozBrowserFrame.match_type(), PseudoClassMatchType::Snapshot);
+    assert_eq!(NonTSPseudoClass::MozTableBorderNonzero.match_type(), PseudoClassMatchType::Snapshot);
+
+    assert_eq!(NonTSPseudoClass::MozBrowserFrame.state_flag(), ElementState::empty());
+    assert_eq!(NonTSPseudoClass::MozTableBorderNonzero.state_flag(), ElementState::empty());
+}
+
+#[test]
+fn pseudo_element_atom_round_trips_through_into_atom() {
+    let pseudo = PseudoElement::from_atom(&atom!(":before")).unwrap();
+    assert_eq!(pseudo.into_atom(), atom!(":before"));
+}
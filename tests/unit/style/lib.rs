@@ -24,6 +24,8 @@ extern crate util;
 mod atomic_refcell;
 mod attr;
 mod cache;
+#[cfg(feature = "gecko")]
+mod gecko_selector_parser;
 mod logical_geometry;
 mod media_queries;
 mod owning_handle;